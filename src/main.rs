@@ -1,9 +1,20 @@
 use clap::{arg, Command};
 use hidapi::{self, HidApi};
 use regex::Regex;
-use std::{error::Error, ffi::CString, string::String, vec::Vec};
+use redb::{Database, ReadableTable, TableDefinition};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use std::{
+    error::Error,
+    ffi::CString,
+    path::PathBuf,
+    string::String,
+    sync::mpsc::RecvTimeoutError,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    vec::Vec,
+};
 
 const REPORT_ID: u8 = 1;
+const AMBIENT_REPORT_ID: u8 = 0x0a;
 
 const MIN_BRIGHTNESS: u32 = 400;
 const MAX_BRIGHTNESS: u32 = 60000;
@@ -13,11 +24,14 @@ const STUDIO_DISPLAY_PRODUCT_ID: u16 = 0x1114;
 const STUDIO_DISPLAY_VENDOR_ID: u16 = 0x05ac;
 const STUDIO_DISPLAY_INTERFACE_NR: i32 = 0x7;
 
-fn get_brightness(handle: &mut hidapi::HidDevice) -> Result<u32, Box<dyn Error>> {
-    let mut buf = Vec::with_capacity(7); // report id, 4 bytes brightness, 2 bytes unknown
-    buf.push(REPORT_ID);
-    buf.extend(0_u32.to_le_bytes());
-    buf.extend(0_u16.to_le_bytes());
+// Read a feature report, returning the report id followed by `len` payload bytes.
+fn feature_report_get(
+    handle: &mut hidapi::HidDevice,
+    report_id: u8,
+    len: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut buf = vec![0_u8; len + 1];
+    buf[0] = report_id;
     let size = handle.get_feature_report(&mut buf)?;
     if size != buf.len() {
         Err(format!(
@@ -26,37 +40,173 @@ fn get_brightness(handle: &mut hidapi::HidDevice) -> Result<u32, Box<dyn Error>>
             size
         ))?
     }
+    return Ok(buf);
+}
+
+// Write a feature report made of the report id followed by `data`.
+fn feature_report_set(
+    handle: &mut hidapi::HidDevice,
+    report_id: u8,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut buf = Vec::with_capacity(data.len() + 1);
+    buf.push(report_id);
+    buf.extend_from_slice(data);
+    handle.send_feature_report(&buf)?;
+    Ok(())
+}
+
+fn get_brightness(handle: &mut hidapi::HidDevice) -> Result<u32, Box<dyn Error>> {
+    let buf = feature_report_get(handle, REPORT_ID, 6)?; // 4 bytes brightness, 2 bytes unknown
     let brightness = u32::from_le_bytes(buf[1..5].try_into()?);
     return Ok(brightness);
 }
 
-fn get_brightness_percent(handle: &mut hidapi::HidDevice) -> Result<u8, Box<dyn Error>> {
-    let value = (get_brightness(handle)? - MIN_BRIGHTNESS) as f32;
+fn get_ambient_lux(handle: &mut hidapi::HidDevice) -> Result<f32, Box<dyn Error>> {
+    let buf = feature_report_get(handle, AMBIENT_REPORT_ID, 6)?; // 4 bytes lux, 2 bytes unknown
+    let lux = u32::from_le_bytes(buf[1..5].try_into()?);
+    return Ok(lux as f32);
+}
+
+fn raw_to_percent(raw: u32) -> u8 {
+    let value = (raw.saturating_sub(MIN_BRIGHTNESS)) as f32;
     let value_percent = (value / BRIGHTNESS_RANGE as f32 * 100.0) as u8;
-    return Ok(value_percent);
+    return value_percent;
+}
+
+fn get_brightness_percent(handle: &mut hidapi::HidDevice) -> Result<u8, Box<dyn Error>> {
+    return Ok(raw_to_percent(get_brightness(handle)?));
 }
 
 fn set_brightness(handle: &mut hidapi::HidDevice, brightness: u32) -> Result<(), Box<dyn Error>> {
-    let mut buf = Vec::with_capacity(7); // report id, 4 bytes brightness, 2 bytes unknown
-    buf.push(REPORT_ID);
-    buf.extend(brightness.to_le_bytes());
-    buf.extend(0_u16.to_le_bytes());
-    handle.send_feature_report(&mut buf)?;
+    let mut data = Vec::with_capacity(6); // 4 bytes brightness, 2 bytes unknown
+    data.extend(brightness.to_le_bytes());
+    data.extend(0_u16.to_le_bytes());
+    feature_report_set(handle, REPORT_ID, &data)?;
     Ok(())
 }
 
-fn set_brightness_percent(
-    handle: &mut hidapi::HidDevice,
-    brightness: u8,
-) -> Result<(), Box<dyn Error>> {
+fn percent_to_nits(brightness: u8) -> u32 {
     let nits =
         (((brightness as f32 / 100.0) * BRIGHTNESS_RANGE as f32) + MIN_BRIGHTNESS as f32) as u32;
     let nits = std::cmp::min(nits, MAX_BRIGHTNESS);
     let nits = std::cmp::max(nits, MIN_BRIGHTNESS);
-    set_brightness(handle, nits)?;
+    return nits;
+}
+
+fn set_brightness_percent(
+    handle: &mut hidapi::HidDevice,
+    brightness: u8,
+) -> Result<(), Box<dyn Error>> {
+    set_brightness(handle, percent_to_nits(brightness))?;
+    Ok(())
+}
+
+fn fade_brightness_percent(
+    handle: &mut hidapi::HidDevice,
+    brightness: u8,
+    duration_ms: u64,
+    steps: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    const TICK_MS: u64 = 16;
+    let start = get_brightness(handle)? as f32;
+    let end = percent_to_nits(brightness) as f32;
+    let steps = steps.unwrap_or_else(|| std::cmp::max(1, duration_ms / TICK_MS));
+    for i in 1..=steps {
+        let t = i as f32 / steps as f32;
+        let eased = t * t * (3.0 - 2.0 * t); // smoothstep 3t² - 2t³
+        let nits = (start + (end - start) * eased).round() as u32;
+        let nits = nits.clamp(MIN_BRIGHTNESS, MAX_BRIGHTNESS);
+        set_brightness(handle, nits)?;
+        if i < steps {
+            std::thread::sleep(Duration::from_millis(TICK_MS));
+        }
+    }
+    Ok(())
+}
+
+const CACHE_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("brightness");
+const CACHE_TTL_SECS: u64 = 5;
+
+fn now_secs() -> Result<u64, Box<dyn Error>> {
+    return Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+}
+
+fn cache_path() -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .unwrap_or_else(|| PathBuf::from("/tmp"));
+    return base.join("asdbctl").join("cache.redb");
+}
+
+fn open_cache() -> Result<Database, Box<dyn Error>> {
+    let path = cache_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    return Ok(Database::create(path)?);
+}
+
+fn cache_get(db: &Database, serial: &str) -> Result<Option<u32>, Box<dyn Error>> {
+    let tx = db.begin_read()?;
+    let table = match tx.open_table(CACHE_TABLE) {
+        Ok(t) => t,
+        Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(value) = table.get(serial)? {
+        let bytes = value.value();
+        if bytes.len() == 12 {
+            let brightness = u32::from_le_bytes(bytes[0..4].try_into()?);
+            let ts = u64::from_le_bytes(bytes[4..12].try_into()?);
+            if now_secs()?.saturating_sub(ts) <= CACHE_TTL_SECS {
+                return Ok(Some(brightness));
+            }
+        }
+    }
+    return Ok(None);
+}
+
+fn cache_put(db: &Database, serial: &str, brightness: u32) -> Result<(), Box<dyn Error>> {
+    let mut bytes = [0_u8; 12];
+    bytes[0..4].copy_from_slice(&brightness.to_le_bytes());
+    bytes[4..12].copy_from_slice(&now_secs()?.to_le_bytes());
+    let tx = db.begin_write()?;
+    {
+        let mut table = tx.open_table(CACHE_TABLE)?;
+        table.insert(serial, bytes.as_slice())?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn cache_clear() -> Result<(), Box<dyn Error>> {
+    let path = cache_path();
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
     Ok(())
 }
 
+// Return the raw brightness, preferring a fresh cached value over a HID round-trip.
+fn cached_brightness(
+    handle: &mut hidapi::HidDevice,
+    cache: Option<&Database>,
+    serial: &str,
+) -> Result<u32, Box<dyn Error>> {
+    if let Some(db) = cache {
+        if let Some(raw) = cache_get(db, serial)? {
+            return Ok(raw);
+        }
+    }
+    let raw = get_brightness(handle)?;
+    if let Some(db) = cache {
+        cache_put(db, serial, raw)?;
+    }
+    return Ok(raw);
+}
+
 fn list_displays() -> Result<Vec<String>, Box<dyn Error>> {
     let mut result = Vec::new();
     let re = Regex::new(r"asdbl-[0-9A-F]{8}-[0-9A-F]{16}$")?;
@@ -88,10 +238,259 @@ fn list_displays_hapi(hapi: &HidApi) -> Result<Vec<String>, Box<dyn Error>> {
     return Ok(result);
 }
 
+fn enumerate_displays() -> Result<(HidApi, Vec<String>), Box<dyn Error>> {
+    let mut displays = list_displays()?;
+    let hapi = if displays.len() > 0 {
+        HidApi::new_without_enumerate()?
+    } else {
+        HidApi::new()?
+    };
+    if displays.len() <= 0 {
+        displays = list_displays_hapi(&hapi)?;
+    }
+    return Ok((hapi, displays));
+}
+
+fn resolve_targets(
+    hapi: &HidApi,
+    displays: &[String],
+    selector: Option<&str>,
+    all: bool,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    if displays.is_empty() {
+        return Err("No Apple Studio Display found")?;
+    }
+    if all {
+        return Ok((0..displays.len()).collect());
+    }
+    let selector = match selector {
+        Some(s) => s,
+        None => return Ok(vec![0]),
+    };
+    if let Ok(index) = selector.parse::<usize>() {
+        if index < displays.len() {
+            return Ok(vec![index]);
+        }
+    }
+    for (i, path) in displays.iter().enumerate() {
+        if display_serial(hapi, path) == selector {
+            return Ok(vec![i]);
+        }
+    }
+    return Err(format!("No display matching '{}'", selector))?;
+}
+
+fn parse_report_id(s: &str) -> Result<u8, Box<dyn Error>> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => Ok(u8::from_str_radix(hex, 16)?),
+        None => Ok(s.parse::<u8>()?),
+    }
+}
+
+fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let cleaned = s.trim().replace([' ', ':'], "");
+    let cleaned = cleaned
+        .strip_prefix("0x")
+        .or_else(|| cleaned.strip_prefix("0X"))
+        .unwrap_or(&cleaned);
+    if cleaned.len() % 2 != 0 {
+        Err("hex payload must have an even number of digits")?
+    }
+    let mut bytes = Vec::with_capacity(cleaned.len() / 2);
+    for i in (0..cleaned.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&cleaned[i..i + 2], 16)?);
+    }
+    return Ok(bytes);
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn display_serial(hapi: &HidApi, path: &str) -> String {
+    // The udev rule names the device node asdbl-XXXXXXXX-XXXXXXXXXXXXXXXX; the
+    // trailing component uniquely identifies the panel and is stable across boots.
+    let name = path.rsplit('/').next().unwrap_or(path);
+    if let Some(serial) = name.strip_prefix("asdbl-") {
+        return serial.to_string();
+    }
+    // Raw-HID fallback: the node name (hidrawN) is not stable across replug, so
+    // use the device's real HID serial number when the enumeration exposes it.
+    for d in hapi.device_list() {
+        if d.path().to_str() == Ok(path) {
+            if let Some(serial) = d.serial_number() {
+                return serial.to_string();
+            }
+        }
+    }
+    return name.to_string();
+}
+
+fn serve(
+    handle: &mut hidapi::HidDevice,
+    serial: &str,
+    broker: &str,
+    port: u16,
+    prefix: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+    interval: u64,
+) -> Result<(), Box<dyn Error>> {
+    let state_topic = format!("{}/{}/brightness/state", prefix, serial);
+    let set_topic = format!("{}/{}/brightness/set", prefix, serial);
+    let config_topic = format!("homeassistant/light/asdbctl_{}/config", serial);
+
+    let mut options = MqttOptions::new(format!("asdbctl-{}", serial), broker, port);
+    options.set_keep_alive(Duration::from_secs(5));
+    if let Some(user) = username {
+        options.set_credentials(user, password.unwrap_or_default());
+    }
+
+    let (client, mut connection) = Client::new(options, 10);
+    client.subscribe(&set_topic, QoS::AtLeastOnce)?;
+
+    // Home-Assistant MQTT discovery so the panel shows up as a dimmable light.
+    let discovery = format!(
+        concat!(
+            "{{\"name\":\"Studio Display {serial}\",",
+            "\"unique_id\":\"asdbctl_{serial}\",",
+            "\"schema\":\"basic\",",
+            "\"state_topic\":\"{state}\",",
+            "\"command_topic\":\"{set}\",",
+            "\"brightness_state_topic\":\"{state}\",",
+            "\"brightness_command_topic\":\"{set}\",",
+            "\"brightness_scale\":100,",
+            "\"on_command_type\":\"brightness\",",
+            "\"payload_on\":\"100\",\"payload_off\":\"0\"}}"
+        ),
+        serial = serial,
+        state = state_topic,
+        set = set_topic
+    );
+    client.publish(&config_topic, QoS::AtLeastOnce, true, discovery)?;
+
+    // Incoming `set` messages drive the panel; the current value is re-published
+    // on its own `--interval` timer, independent of the MQTT keep-alive. A broker
+    // error is logged and swallowed so rumqttc's built-in reconnect keeps the
+    // daemon alive across transient outages.
+    let interval = Duration::from_secs(interval);
+    let mut last_publish = Instant::now()
+        .checked_sub(interval)
+        .unwrap_or_else(Instant::now);
+    loop {
+        match connection.recv_timeout(Duration::from_millis(500)) {
+            Ok(Ok(Event::Incoming(Packet::Publish(p)))) if p.topic == set_topic => {
+                let payload = match std::str::from_utf8(&p.payload) {
+                    Ok(s) => s.trim().to_owned(),
+                    Err(e) => {
+                        eprintln!("ignoring non-UTF8 payload on {}: {}", set_topic, e);
+                        continue;
+                    }
+                };
+                if let Ok(value) = payload.parse::<u8>() {
+                    let value = std::cmp::min(value, 100);
+                    if let Err(e) = set_brightness_percent(handle, value) {
+                        eprintln!("failed to set brightness: {}", e);
+                        continue;
+                    }
+                    if let Err(e) =
+                        client.publish(&state_topic, QoS::AtLeastOnce, false, value.to_string())
+                    {
+                        eprintln!("failed to publish state: {}", e);
+                    }
+                    last_publish = Instant::now();
+                }
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => eprintln!("mqtt connection error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if last_publish.elapsed() >= interval {
+            match get_brightness_percent(handle) {
+                Ok(brightness) => {
+                    if let Err(e) =
+                        client.publish(&state_topic, QoS::AtLeastOnce, false, brightness.to_string())
+                    {
+                        eprintln!("failed to publish state: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("failed to read brightness: {}", e),
+            }
+            last_publish = Instant::now();
+        }
+    }
+    return Ok(());
+}
+
+#[allow(clippy::too_many_arguments)]
+fn auto(
+    handle: &mut hidapi::HidDevice,
+    min_pct: u8,
+    max_pct: u8,
+    lux_min: f32,
+    lux_max: f32,
+    interval: u64,
+    alpha: f32,
+    deadband: u8,
+) -> Result<(), Box<dyn Error>> {
+    if lux_min <= 0.0 {
+        Err("--lux-min must be greater than 0")?
+    }
+    if lux_max <= lux_min {
+        Err("--lux-max must be greater than --lux-min")?
+    }
+    let mut ema: Option<f32> = None;
+    loop {
+        // Clamp the raw sample into the configured window before smoothing so a
+        // single dark or blown-out reading can never push the EMA out of range.
+        let sample = get_ambient_lux(handle)?.clamp(lux_min, lux_max);
+        let smoothed = match ema {
+            Some(prev) => alpha * sample + (1.0 - alpha) * prev,
+            None => sample,
+        };
+        ema = Some(smoothed);
+
+        let t = ((smoothed.ln() - lux_min.ln()) / (lux_max.ln() - lux_min.ln())).clamp(0.0, 1.0);
+        let target = (min_pct as f32 + t * (max_pct as f32 - min_pct as f32)).round() as u8;
+        let current = get_brightness_percent(handle)?;
+        if (target as i32 - current as i32).unsigned_abs() as u8 > deadband {
+            set_brightness_percent(handle, target)?;
+        }
+        std::thread::sleep(Duration::from_secs(interval));
+    }
+}
+
 fn cli() -> Command {
     Command::new("asdbctl")
         .about("Tool to get or set the brightness for Apple Studio Displays")
         .subcommand_required(true)
+        .arg(
+            arg!(-d --display <DISPLAY> "Target a display by index or serial")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(-a --all "Apply to every detected display")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"no-cache" "Bypass the persistent brightness cache")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .subcommand(Command::new("list").about("List the detected displays"))
+        .subcommand(
+            Command::new("cache")
+                .about("Manage the persistent brightness cache")
+                .subcommand_required(true)
+                .subcommand(Command::new("clear").about("Remove all cached brightness values")),
+        )
         .subcommand(Command::new("get").about("Get the current brightness in %"))
         .subcommand(
             Command::new("set")
@@ -100,6 +499,17 @@ fn cli() -> Command {
                     arg!(<BRIGHTNESS> "The remote to target")
                         .value_parser(clap::value_parser!(u8).range(0..101)),
                 )
+                .arg(
+                    arg!(--fade <MS> "Fade to the target over the given milliseconds")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--steps <N> "Number of interpolation steps for the fade")
+                        .required(false)
+                        .requires("fade")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
                 .arg_required_else_help(true),
         )
         .subcommand(
@@ -110,6 +520,17 @@ fn cli() -> Command {
                         .default_value("10")
                         .value_parser(clap::value_parser!(u8).range(1..101)),
                 )
+                .arg(
+                    arg!(--fade <MS> "Fade to the target over the given milliseconds")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--steps <N> "Number of interpolation steps for the fade")
+                        .required(false)
+                        .requires("fade")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
                 .about("Increase the brightness"),
         )
         .subcommand(
@@ -120,49 +541,270 @@ fn cli() -> Command {
                         .default_value("10")
                         .value_parser(clap::value_parser!(u8).range(1..101)),
                 )
+                .arg(
+                    arg!(--fade <MS> "Fade to the target over the given milliseconds")
+                        .required(false)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--steps <N> "Number of interpolation steps for the fade")
+                        .required(false)
+                        .requires("fade")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
                 .about("Decrease the brightness"),
         )
+        .subcommand(
+            Command::new("serve")
+                .about("Run as a daemon exposing brightness over MQTT")
+                .arg(
+                    arg!(--broker <HOST> "MQTT broker host")
+                        .required(false)
+                        .default_value("localhost"),
+                )
+                .arg(
+                    arg!(--port <PORT> "MQTT broker port")
+                        .required(false)
+                        .default_value("1883")
+                        .value_parser(clap::value_parser!(u16)),
+                )
+                .arg(
+                    arg!(--"topic-prefix" <PREFIX> "Prefix for the state/set topics")
+                        .required(false)
+                        .default_value("asdbctl"),
+                )
+                .arg(arg!(--username <USERNAME> "MQTT username").required(false))
+                .arg(arg!(--password <PASSWORD> "MQTT password").required(false))
+                .arg(
+                    arg!(--interval <SECONDS> "Seconds between state publishes")
+                        .required(false)
+                        .default_value("30")
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("auto")
+                .about("Track the ambient light sensor and adjust brightness")
+                .arg(
+                    arg!(--"min-pct" <PCT> "Brightness at or below lux-min")
+                        .required(false)
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(u8).range(0..101)),
+                )
+                .arg(
+                    arg!(--"max-pct" <PCT> "Brightness at or above lux-max")
+                        .required(false)
+                        .default_value("100")
+                        .value_parser(clap::value_parser!(u8).range(0..101)),
+                )
+                .arg(
+                    arg!(--"lux-min" <LUX> "Lux mapped to min-pct")
+                        .required(false)
+                        .default_value("1")
+                        .value_parser(clap::value_parser!(f32)),
+                )
+                .arg(
+                    arg!(--"lux-max" <LUX> "Lux mapped to max-pct")
+                        .required(false)
+                        .default_value("1000")
+                        .value_parser(clap::value_parser!(f32)),
+                )
+                .arg(
+                    arg!(--interval <SECONDS> "Seconds between sensor reads")
+                        .required(false)
+                        .default_value("2")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--alpha <ALPHA> "Exponential smoothing factor (0..1)")
+                        .required(false)
+                        .default_value("0.2")
+                        .value_parser(clap::value_parser!(f32)),
+                )
+                .arg(
+                    arg!(--deadband <PCT> "Minimum change before writing")
+                        .required(false)
+                        .default_value("2")
+                        .value_parser(clap::value_parser!(u8).range(0..101)),
+                ),
+        )
+        .subcommand(
+            Command::new("raw")
+                .about("Read or write arbitrary HID feature reports")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("get")
+                        .about("Read a feature report and print it as hex")
+                        .arg(arg!(<REPORT_ID> "Report id (decimal or 0x-prefixed hex)"))
+                        .arg(
+                            arg!(<LEN> "Number of payload bytes to read")
+                                .value_parser(clap::value_parser!(usize)),
+                        )
+                        .arg_required_else_help(true),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Write a crafted feature report payload")
+                        .arg(arg!(<REPORT_ID> "Report id (decimal or 0x-prefixed hex)"))
+                        .arg(arg!(<HEX> "Payload bytes as hex, e.g. 30ea00"))
+                        .arg_required_else_help(true),
+                ),
+        )
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let matches = cli().get_matches();
-    let mut displays = list_displays()?;
-    let hapi = if displays.len() > 0 {
-        HidApi::new_without_enumerate()?
-    } else {
-        HidApi::new()?
-    };
-    if displays.len() <= 0 {
-        displays = list_displays_hapi(&hapi)?;
+
+    if let Some(("cache", cache_matches)) = matches.subcommand() {
+        match cache_matches.subcommand() {
+            Some(("clear", _)) => cache_clear()?,
+            _ => unreachable!(),
+        }
+        return Ok(());
     }
-    if displays.len() <= 0 {
-        return Err("No Apple Studio Display found")?;
+
+    let (hapi, displays) = enumerate_displays()?;
+
+    if let Some(("list", _)) = matches.subcommand() {
+        if displays.is_empty() {
+            return Err("No Apple Studio Display found")?;
+        }
+        for (index, path) in displays.iter().enumerate() {
+            println!("{}: {}", index, display_serial(&hapi, path));
+        }
+        return Ok(());
+    }
+
+    let selector = matches.get_one::<String>("display").map(|s| s.as_str());
+    let all = matches.get_flag("all");
+    if all {
+        if let Some(("serve" | "auto", _)) = matches.subcommand() {
+            return Err("--all is not supported for daemon subcommands; target a single display with --display")?;
+        }
     }
-    let display = displays.first().unwrap().as_str();
-    let dev_path = CString::new(display)?;
-    let mut handle = hapi.open_path(&dev_path)?;
-    match matches.subcommand() {
-        Some(("get", _)) => {
-            let brightness = get_brightness_percent(&mut handle)?;
-            println!("brightness {}", brightness);
-        }
-        Some(("set", sub_matches)) => {
-            let brightness = *sub_matches.get_one::<u8>("BRIGHTNESS").expect("required");
-            set_brightness_percent(&mut handle, brightness)?;
-        }
-        Some(("up", sub_matches)) => {
-            let step = *sub_matches.get_one::<u8>("step").expect("required");
-            let brightness = get_brightness_percent(&mut handle)?;
-            let new_brightness = std::cmp::min(100, brightness + step);
-            set_brightness_percent(&mut handle, new_brightness)?;
-        }
-        Some(("down", sub_matches)) => {
-            let step = *sub_matches.get_one::<u8>("step").expect("required");
-            let brightness = get_brightness_percent(&mut handle)?;
-            let new_brightness = std::cmp::min(100, brightness - step);
-            set_brightness_percent(&mut handle, new_brightness)?;
-        }
-        _ => unreachable!(),
+    let targets = resolve_targets(&hapi, &displays, selector, all)?;
+    let report_serial = targets.len() > 1;
+
+    let cache = if matches.get_flag("no-cache") {
+        None
+    } else {
+        Some(open_cache()?)
+    };
+
+    for index in targets {
+        let display = displays[index].as_str();
+        let serial = display_serial(&hapi, display);
+        let dev_path = CString::new(display)?;
+        let mut handle = hapi.open_path(&dev_path)?;
+        match matches.subcommand() {
+            Some(("get", _)) => {
+                let brightness = get_brightness_percent(&mut handle)?;
+                if report_serial {
+                    println!("{} brightness {}", serial, brightness);
+                } else {
+                    println!("brightness {}", brightness);
+                }
+            }
+            Some(("set", sub_matches)) => {
+                let brightness = *sub_matches.get_one::<u8>("BRIGHTNESS").expect("required");
+                let fade = sub_matches.get_one::<u64>("fade").copied();
+                let steps = sub_matches.get_one::<u64>("steps").copied();
+                match fade {
+                    Some(ms) => fade_brightness_percent(&mut handle, brightness, ms, steps)?,
+                    None => set_brightness_percent(&mut handle, brightness)?,
+                }
+                if let Some(db) = cache.as_ref() {
+                    cache_put(db, &serial, percent_to_nits(brightness))?;
+                }
+            }
+            Some(("up", sub_matches)) => {
+                let step = *sub_matches.get_one::<u8>("step").expect("required");
+                let fade = sub_matches.get_one::<u64>("fade").copied();
+                let steps = sub_matches.get_one::<u64>("steps").copied();
+                let brightness = raw_to_percent(cached_brightness(&mut handle, cache.as_ref(), &serial)?);
+                let new_brightness = std::cmp::min(100, brightness + step);
+                match fade {
+                    Some(ms) => fade_brightness_percent(&mut handle, new_brightness, ms, steps)?,
+                    None => set_brightness_percent(&mut handle, new_brightness)?,
+                }
+                if let Some(db) = cache.as_ref() {
+                    cache_put(db, &serial, percent_to_nits(new_brightness))?;
+                }
+            }
+            Some(("down", sub_matches)) => {
+                let step = *sub_matches.get_one::<u8>("step").expect("required");
+                let fade = sub_matches.get_one::<u64>("fade").copied();
+                let steps = sub_matches.get_one::<u64>("steps").copied();
+                let brightness = raw_to_percent(cached_brightness(&mut handle, cache.as_ref(), &serial)?);
+                let new_brightness = brightness.saturating_sub(step);
+                match fade {
+                    Some(ms) => fade_brightness_percent(&mut handle, new_brightness, ms, steps)?,
+                    None => set_brightness_percent(&mut handle, new_brightness)?,
+                }
+                if let Some(db) = cache.as_ref() {
+                    cache_put(db, &serial, percent_to_nits(new_brightness))?;
+                }
+            }
+            Some(("serve", sub_matches)) => {
+                let broker = sub_matches.get_one::<String>("broker").expect("default");
+                let port = *sub_matches.get_one::<u16>("port").expect("default");
+                let prefix = sub_matches.get_one::<String>("topic-prefix").expect("default");
+                let username = sub_matches.get_one::<String>("username").map(|s| s.as_str());
+                let password = sub_matches.get_one::<String>("password").map(|s| s.as_str());
+                let interval = *sub_matches.get_one::<u64>("interval").expect("default");
+                serve(
+                    &mut handle,
+                    &serial,
+                    broker,
+                    port,
+                    prefix,
+                    username,
+                    password,
+                    interval,
+                )?;
+            }
+            Some(("auto", sub_matches)) => {
+                let min_pct = *sub_matches.get_one::<u8>("min-pct").expect("default");
+                let max_pct = *sub_matches.get_one::<u8>("max-pct").expect("default");
+                let lux_min = *sub_matches.get_one::<f32>("lux-min").expect("default");
+                let lux_max = *sub_matches.get_one::<f32>("lux-max").expect("default");
+                let interval = *sub_matches.get_one::<u64>("interval").expect("default");
+                let alpha = *sub_matches.get_one::<f32>("alpha").expect("default");
+                let deadband = *sub_matches.get_one::<u8>("deadband").expect("default");
+                auto(
+                    &mut handle,
+                    min_pct,
+                    max_pct,
+                    lux_min,
+                    lux_max,
+                    interval,
+                    alpha,
+                    deadband,
+                )?;
+            }
+            Some(("raw", raw_matches)) => match raw_matches.subcommand() {
+                Some(("get", m)) => {
+                    let report_id =
+                        parse_report_id(m.get_one::<String>("REPORT_ID").expect("required"))?;
+                    let len = *m.get_one::<usize>("LEN").expect("required");
+                    let buf = feature_report_get(&mut handle, report_id, len)?;
+                    let hex = to_hex(&buf[1..]);
+                    if report_serial {
+                        println!("{} {}", serial, hex);
+                    } else {
+                        println!("{}", hex);
+                    }
+                }
+                Some(("set", m)) => {
+                    let report_id =
+                        parse_report_id(m.get_one::<String>("REPORT_ID").expect("required"))?;
+                    let data = parse_hex_bytes(m.get_one::<String>("HEX").expect("required"))?;
+                    feature_report_set(&mut handle, report_id, &data)?;
+                }
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
     }
     return Ok(());
 }